@@ -0,0 +1,185 @@
+//
+// multilist/examples/tasks.rs
+//
+// Copyright (c) 2015 Mozilla Foundation
+//
+// Skeleton code that shows how a multilist might be used in an operating system kernel to manage
+// tasks. This lives outside the `no_std` library crate because it relies on `println!`.
+//
+
+extern crate multilist;
+
+use multilist::{Allocator, Multilist};
+use std::cell::Cell;
+use std::cell::UnsafeCell;
+use std::ptr;
+
+#[derive(Debug)]
+struct TaskStruct {
+    pid: i32,
+    gid: i32,
+}
+
+const TASK_LIST: usize = 0;
+const RUN_LIST: usize = 1;
+const WAITING_LIST: usize = 2;
+
+fn main() {
+    let mut multilist = Multilist::new(3);
+    multilist.push_back(TASK_LIST, TaskStruct {
+        pid: 1,
+        gid: 2,
+    });
+    multilist.push_back(TASK_LIST, TaskStruct {
+        pid: 3,
+        gid: 4,
+    });
+    multilist.push_back(TASK_LIST, TaskStruct {
+        pid: 5,
+        gid: 6,
+    });
+    println!("After adding 3 tasks to task list:");
+    dump_list(&multilist);
+
+    // The kernel's idle task always runs first, so it goes on the front of the task list rather
+    // than the back.
+    multilist.push_front(TASK_LIST, TaskStruct {
+        pid: 0,
+        gid: 0,
+    });
+    println!("\nAfter push_front'ing the idle task onto the task list:");
+    dump_list(&multilist);
+
+    // Walk the task list with a read-only cursor to build the run list in scheduling order
+    // pid 5, pid 1, pid 3, instead of re-iterating from the head via `skip(n).next()` for each
+    // lookup.
+    let mut cursor = multilist.cursor(TASK_LIST);
+    cursor.move_next();
+    cursor.move_next();
+    cursor.move_next();
+    let pid_5 = cursor.current().unwrap();
+
+    let mut cursor = multilist.cursor(TASK_LIST);
+    cursor.move_next();
+    let pid_1 = cursor.current().unwrap();
+
+    let mut cursor = multilist.cursor(TASK_LIST);
+    cursor.move_next();
+    cursor.move_next();
+    let pid_3 = cursor.current().unwrap();
+
+    multilist.push_back_existing(RUN_LIST, pid_5);
+    multilist.push_back_existing(RUN_LIST, pid_1);
+    multilist.push_back_existing(RUN_LIST, pid_3);
+    println!("\nAfter adding 3 tasks to run list in scheduling order:");
+    dump_list(&multilist);
+
+    // An editing cursor lets us detach the second original task (pid 3) from the task list in
+    // O(1) time, without walking from the head to find it first. It stays on the run list, since
+    // `remove_current` only detaches it from the cursor's own list.
+    let mut cursor = multilist.cursor_mut(TASK_LIST);
+    cursor.move_next();
+    cursor.move_next();
+    cursor.remove_current();
+    println!("\nAfter removing the second task from the task list:");
+    dump_list(&multilist);
+
+    multilist.push_back(TASK_LIST, TaskStruct {
+        pid: 7,
+        gid: 8,
+    });
+    println!("\nAfter adding a new task to the task list:");
+    dump_list(&multilist);
+
+    // Cut the run list in two after its second entry (pid 1), handing the detached tail off as
+    // its own multilist — as a scheduler might do to give a second CPU its own run queue.
+    // split_after only re-homes elements that belong to no list but this one, so the split has
+    // to land after pid 1: pid 5 and pid 1 are still linked into the task list too, but pid 3 (the
+    // only element after pid 1) was already detached from it above.
+    let mut run_list_iter = multilist.iter(RUN_LIST);
+    run_list_iter.next().unwrap();
+    let pid_1_in_run_list = run_list_iter.next().unwrap();
+    let other_cpu_run_list = multilist.split_after(RUN_LIST, pid_1_in_run_list);
+    println!("\nAfter splitting the run list after its second entry:");
+    dump_list(&multilist);
+    println!("Other CPU's run list:");
+    for task in other_cpu_run_list.iter(RUN_LIST) {
+        println!("{:?}", &*task);
+    }
+
+    // A task that just finished waiting on I/O joins the waiting list first, then the whole
+    // waiting list is merged onto the tail of the run list in O(1) once the device is ready.
+    multilist.push_back(WAITING_LIST, TaskStruct {
+        pid: 11,
+        gid: 11,
+    });
+    multilist.append_list(RUN_LIST, WAITING_LIST);
+    println!("\nAfter merging the waiting list onto the tail of the run list:");
+    dump_list(&multilist);
+
+    multilist.pop_back(RUN_LIST);
+    println!("\nAfter removing the last task on the run list entirely:");
+    dump_list(&multilist);
+
+    // Kernels often can't assume a working global heap for every allocation; a bump allocator
+    // drawing from a static arena is a common substitute for short-lived, per-object holders.
+    let arena = BumpAllocator::new();
+    let boot_tasks = Multilist::new_in(1, &arena);
+    boot_tasks.push_back(TASK_LIST, TaskStruct {
+        pid: 9,
+        gid: 9,
+    });
+    println!("\nBoot-time tasks, allocated from a bump arena instead of the global heap:");
+    for task in boot_tasks.iter(TASK_LIST) {
+        println!("{:?}", &*task);
+    }
+}
+
+fn dump_list(multilist: &Multilist<TaskStruct>) {
+    println!("Tasks in task order:");
+    for task in multilist.iter(TASK_LIST) {
+        println!("{:?}", &*task);
+    }
+    println!("Tasks in run order:");
+    for task in multilist.iter(RUN_LIST) {
+        println!("{:?}", &*task);
+    }
+    println!("Tasks in run order, reversed:");
+    for task in multilist.iter(RUN_LIST).rev() {
+        println!("{:?}", &*task);
+    }
+}
+
+/// A minimal bump allocator over a fixed-size arena, of the kind a kernel might hand out for
+/// short-lived, single-allocation holders instead of routing through the global heap.
+struct BumpAllocator {
+    arena: UnsafeCell<[u8; 4096]>,
+    offset: Cell<usize>,
+}
+
+impl BumpAllocator {
+    fn new() -> BumpAllocator {
+        BumpAllocator {
+            arena: UnsafeCell::new([0; 4096]),
+            offset: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Allocator for &'a BumpAllocator {
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        let base = (*self.arena.get()).as_mut_ptr();
+        let start = self.offset.get();
+        let aligned = (start + align - 1) & !(align - 1);
+        if aligned + size > (*self.arena.get()).len() {
+            return ptr::null_mut()
+        }
+        self.offset.set(aligned + size);
+        base.offset(aligned as isize)
+    }
+
+    unsafe fn deallocate(&self, _ptr: *mut u8, _size: usize, _align: usize) {
+        // A bump allocator never reclaims individual allocations; the whole arena is freed at
+        // once when it goes out of scope.
+    }
+}
@@ -4,16 +4,50 @@
 // Copyright (c) 2015 Mozilla Foundation
 //
 
-#![feature(alloc, core, unsafe_destructor)]
+#![feature(alloc, core, unsafe_destructor, no_std)]
+#![no_std]
 
+#[macro_use]
+extern crate core;
 extern crate alloc;
 
+use alloc::boxed::Box;
 use alloc::heap;
-use std::cell::UnsafeCell;
-use std::iter;
-use std::mem;
-use std::ops::Deref;
-use std::ptr;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::cell::UnsafeCell;
+use core::iter;
+use core::iter::range;
+use core::mem;
+use core::ops::Deref;
+use core::ptr;
+
+/// An allocator capable of backing a multilist's per-object, variable-size element holders. This
+/// mirrors the `Allocator` trait that `std::collections::LinkedList` was parameterized over, but in
+/// the minimal, size-and-align-explicit form this crate needs for its single-allocation holders.
+pub trait Allocator {
+    /// Allocates `size` bytes aligned to `align`, returning a null pointer on failure.
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8;
+
+    /// Deallocates a block previously returned by `allocate()` with the same `size` and `align`.
+    unsafe fn deallocate(&self, ptr: *mut u8, size: usize, align: usize);
+}
+
+/// The default allocator, backed by the global heap (`alloc::heap`).
+#[derive(Copy, Clone)]
+pub struct Global;
+
+impl Allocator for Global {
+    #[inline]
+    unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+        heap::allocate(size, align)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: *mut u8, size: usize, align: usize) {
+        heap::deallocate(ptr, size, align)
+    }
+}
 
 /// An intrusive set of doubly-linked lists, indexed by number. Objects owned by the multilist can
 /// belong to any number of the constituent linked lists. Only one allocation is used per object.
@@ -25,16 +59,23 @@ use std::ptr;
 /// `iter()`. When the multilist is destroyed, all objects within it are destroyed as well; in
 /// this way, the lists *collectively own* the objects.
 ///
+/// The per-object holder is allocated through the pluggable allocator `A`, which defaults to the
+/// global heap. Embedded and kernel users — the audience of the `TaskStruct` example — can supply a
+/// bump or slab allocator instead.
+///
 /// Objects owned by the multilist are normally immutable, but you can use `Cell` or `RefCell` as
-/// usual to make their fields mutable. `multilist` is believed to be a memory-safe design,
-/// although it is possible to leak with incorrect use of `remove_existing()`. Fixing this would
-/// require reference counting the list items.
-pub struct Multilist<Value> {
-    pointers: UnsafeCell<Vec<MultilistListPointers<Value>>>,
+/// usual to make their fields mutable. `multilist` is believed to be a memory-safe design. Each
+/// object tracks the number of lists it currently belongs to, so `remove_existing()` is leak-safe
+/// on its own: removing an object from its last list tears it down and returns its value, with no
+/// need to reach for `pop_back()`/`pop_front()` to avoid leaking it.
+pub struct Multilist<Value, A: Allocator = Global> {
+    pointers: UnsafeCell<Vec<MultilistListPointers<Value, A>>>,
+    object_count: Cell<usize>,
+    allocator: A,
 }
 
 #[unsafe_destructor]
-impl<Value> Drop for Multilist<Value> {
+impl<Value, A: Allocator> Drop for Multilist<Value, A> {
     fn drop(&mut self) {
         for i in range(0, self.list_count()) {
             while self.pop_back(i).is_some() {}
@@ -42,13 +83,22 @@ impl<Value> Drop for Multilist<Value> {
     }
 }
 
-impl<Value> Multilist<Value> {
+impl<Value> Multilist<Value, Global> {
     #[inline]
-    pub fn new(list_count: usize) -> Multilist<Value> {
+    pub fn new(list_count: usize) -> Multilist<Value, Global> {
+        Multilist::new_in(list_count, Global)
+    }
+}
+
+impl<Value, A: Allocator> Multilist<Value, A> {
+    #[inline]
+    pub fn new_in(list_count: usize, allocator: A) -> Multilist<Value, A> {
         Multilist {
             pointers: UnsafeCell::new(iter::repeat(MultilistListPointers::new()).take(list_count as
                                                                                       usize)
                                                                                 .collect()),
+            object_count: Cell::new(0),
+            allocator: allocator,
         }
     }
 
@@ -59,13 +109,26 @@ impl<Value> Multilist<Value> {
         }
     }
 
+    /// Returns the number of elements in one of the lists, in O(1) time.
     #[inline]
-    pub fn is_empty(&self, list_index: usize) -> bool {
+    pub fn len(&self, list_index: usize) -> usize {
         unsafe {
-            (*self.pointers.get())[list_index].head.is_null()
+            (*self.pointers.get())[list_index].len
         }
     }
 
+    #[inline]
+    pub fn is_empty(&self, list_index: usize) -> bool {
+        self.len(list_index) == 0
+    }
+
+    /// Returns the total number of live objects owned by this multilist, regardless of how many
+    /// lists each belongs to, in O(1) time.
+    #[inline]
+    pub fn total_objects(&self) -> usize {
+        self.object_count.get()
+    }
+
     /// Inserts a brand-new element into one of the lists.
     #[inline]
     pub fn push_back(&self, list_index: usize, value: Value) {
@@ -75,29 +138,80 @@ impl<Value> Multilist<Value> {
 
     /// Inserts an element that is already in at least one of the lists into another list.
     #[inline]
-    pub fn push_back_existing(&self, list_index: usize, element: MultilistElement<Value>) {
+    pub fn push_back_existing(&self, list_index: usize, element: MultilistElement<Value, A>) {
         unsafe {
             assert!(element.associated_multilist() == self as *const _);
             let pointers = element.pointers(list_index);
             assert!((*pointers).next.is_null());
             debug_assert!((*pointers).prev.is_null());
-            let list_pointers = &mut (*self.pointers.get())[list_index];
-            if list_pointers.tail.is_null() {
-                list_pointers.head = element.holder as *mut _;
-            } else {
-                (*(*list_pointers.tail).pointers(list_index)).next = element.holder as *mut _;
-                (*pointers).prev = list_pointers.tail;
-            }
+            let tail = (*self.pointers.get())[list_index].tail;
+            self.splice_in(list_index, element, tail, ptr::null_mut());
+        }
+    }
+
+    /// Links `element` into the given list between `prev` and `next`, fixing up the neighbouring
+    /// nodes and the list's head/tail as needed. A null `prev` means the element becomes the new
+    /// head; a null `next` means it becomes the new tail. This is the single chokepoint all
+    /// insertion paths (`push_back_existing`, `push_front_existing`, and the cursor's
+    /// `insert_after`/`insert_before`) go through, so it also bumps `membership_count` — keeping
+    /// that bookkeeping here rather than duplicated at each call site is what makes
+    /// `remove_existing` leak-safe for elements inserted through any of them.
+    #[inline]
+    unsafe fn splice_in(&self,
+                        list_index: usize,
+                        element: MultilistElement<Value, A>,
+                        prev: *mut MultilistElementHolder<Value, A>,
+                        next: *mut MultilistElementHolder<Value, A>) {
+        let pointers = element.pointers(list_index);
+        (*pointers).prev = prev;
+        (*pointers).next = next;
+        let list_pointers = &mut (*self.pointers.get())[list_index];
+        if prev.is_null() {
+            list_pointers.head = element.holder as *mut _;
+        } else {
+            (*(*prev).pointers(list_index)).next = element.holder as *mut _;
+        }
+        if next.is_null() {
             list_pointers.tail = element.holder as *mut _;
+        } else {
+            (*(*next).pointers(list_index)).prev = element.holder as *mut _;
+        }
+        list_pointers.len += 1;
+        let membership_count = &(*element.holder).membership_count;
+        membership_count.set(membership_count.get() + 1);
+    }
+
+    /// Inserts a brand-new element at the front of one of the lists.
+    #[inline]
+    pub fn push_front(&self, list_index: usize, value: Value) {
+        let element = MultilistElement::new(value, self);
+        self.push_front_existing(list_index, element);
+    }
+
+    /// Inserts an element that is already in at least one of the lists at the front of another
+    /// list.
+    #[inline]
+    pub fn push_front_existing(&self, list_index: usize, element: MultilistElement<Value, A>) {
+        unsafe {
+            assert!(element.associated_multilist() == self as *const _);
+            let pointers = element.pointers(list_index);
+            assert!((*pointers).prev.is_null());
+            debug_assert!((*pointers).next.is_null());
+            let head = (*self.pointers.get())[list_index].head;
+            self.splice_in(list_index, element, ptr::null_mut(), head);
         }
     }
 
-    /// Removes an element from one of the lists.
+    /// Removes an element from one of the lists, returning the contained value if that was the last
+    /// list the element belonged to.
     ///
-    /// NB: If the element is no longer a member of any lists, this will leak the element! You
-    /// should use `pop_back()` to remove the element from the last list it's a member of.
+    /// Each element keeps a count of the lists it is a member of. When removal from a list brings
+    /// that count to zero the element is torn down exactly as `pop_back()` tears it down — its
+    /// value is moved out and the holder is freed — and the value is returned. This makes arbitrary
+    /// removal leak-safe: there is no longer any need to reach for `pop_back()` on the last list.
     #[inline]
-    pub fn remove_existing(&self, list_index: usize, element: MultilistElement<Value>) {
+    pub fn remove_existing(&self, list_index: usize, element: MultilistElement<Value, A>)
+                           -> Option<Value> {
         unsafe {
             assert!(element.associated_multilist() == self as *const _);
             let pointers = element.pointers(list_index);
@@ -106,7 +220,7 @@ impl<Value> Multilist<Value> {
                 // Make sure it's actually in the list!
                 assert!(list_pointers.tail == element.holder as *mut _);
 
-                list_pointers.tail = ptr::null_mut();
+                list_pointers.tail = (*pointers).prev;
             } else {
                 (*((*(*pointers).next)).pointers(list_index)).prev = (*pointers).prev;
             }
@@ -115,6 +229,20 @@ impl<Value> Multilist<Value> {
             } else {
                 (*((*(*pointers).prev)).pointers(list_index)).next = (*pointers).next;
             }
+            list_pointers.len -= 1;
+            (*pointers).next = ptr::null_mut();
+            (*pointers).prev = ptr::null_mut();
+
+            let membership_count = &(*element.holder).membership_count;
+            membership_count.set(membership_count.get() - 1);
+            if membership_count.get() == 0 {
+                let value = ptr::read(&(*element.holder).value);
+                let mut element = element;
+                element.destroy();
+                Some(value)
+            } else {
+                None
+            }
         }
     }
 
@@ -124,7 +252,7 @@ impl<Value> Multilist<Value> {
     pub fn pop_back(&mut self, list_index: usize) -> Option<Value> {
         unsafe {
             let tail = (*self.pointers.get())[list_index].tail;
-            let mut element = if !tail.is_null() {
+            let element = if !tail.is_null() {
                 MultilistElement {
                     holder: tail,
                 }
@@ -133,65 +261,226 @@ impl<Value> Multilist<Value> {
             };
             for i in range(0, self.list_count()) {
                 if element.is_in_list(i) {
-                    self.remove_existing(i, element)
+                    if let Some(value) = self.remove_existing(i, element) {
+                        return Some(value)
+                    }
+                }
+            }
+            // A list's only element has null `next` and `prev`, so `is_in_list` misses it; find
+            // those memberships through the list heads.
+            for i in range(0, self.list_count()) {
+                if (*self.pointers.get())[i].head == element.holder as *mut _ {
+                    if let Some(value) = self.remove_existing(i, element) {
+                        return Some(value)
+                    }
                 }
             }
-            let value = ptr::read(&(*element.holder).value);
-            element.destroy();
-            Some(value)
+            None
+        }
+    }
+
+    /// Removes the first element of the given list from all of the lists it's a member of and
+    /// returns it.
+    #[inline]
+    pub fn pop_front(&mut self, list_index: usize) -> Option<Value> {
+        unsafe {
+            let head = (*self.pointers.get())[list_index].head;
+            let element = if !head.is_null() {
+                MultilistElement {
+                    holder: head,
+                }
+            } else {
+                return None
+            };
+            for i in range(0, self.list_count()) {
+                if element.is_in_list(i) {
+                    if let Some(value) = self.remove_existing(i, element) {
+                        return Some(value)
+                    }
+                }
+            }
+            // A list's only element has null `next` and `prev`, so `is_in_list` misses it; find
+            // those memberships through the list heads.
+            for i in range(0, self.list_count()) {
+                if (*self.pointers.get())[i].head == element.holder as *mut _ {
+                    if let Some(value) = self.remove_existing(i, element) {
+                        return Some(value)
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    /// Splits one of the lists in two after the given element, returning a fresh multilist (with
+    /// the same number of constituent lists) whose list `list_index` holds the detached suffix.
+    /// The suffix is unlinked from this multilist by clearing `element`'s `next` pointer and the
+    /// successor's `prev` pointer, and is transferred in O(1) by moving the head and tail pointers.
+    ///
+    /// Because every element records the multilist that owns it — a pointer checked by the
+    /// `assert!`s throughout this module — the moved elements are re-homed to the returned
+    /// multilist. That re-homing points at the new multilist's final address, so the result is
+    /// boxed: a `Multilist` is not `Copy`, and re-homing the moved elements before returning by
+    /// value would leave their `associated_multilist` pointers dangling into a stack frame that
+    /// no longer exists once the caller's binding (at a different address) takes over. Boxing
+    /// gives the result a stable heap address from the moment it is constructed, so the pointers
+    /// written during re-homing stay valid for the box's lifetime.
+    ///
+    /// Re-homing is only sound when a moved element belongs to no list other than `list_index`:
+    /// an element still linked into one of this multilist's other lists would otherwise end up
+    /// with its `associated_multilist` pointing at the *returned* multilist while still being
+    /// physically linked into *this* one, tripping the `assert!`s in `remove_existing` (and thus
+    /// `pop_back`/`pop_front`, and `Drop`) the next time that other list touches it. Rather than
+    /// document this as a silent caller obligation, it's enforced here: a moved element's
+    /// `membership_count` is 1 (its only membership is `list_index`, the one being detached) if
+    /// and only if it belongs to no other list, so this panics instead of corrupting state.
+    #[inline]
+    pub fn split_after(&self, list_index: usize, element: MultilistElement<Value, A>)
+                       -> Box<Multilist<Value, A>> where A: Clone {
+        unsafe {
+            assert!(element.associated_multilist() == self as *const _);
+            let result = Box::new(Multilist::new_in(self.list_count(), self.allocator.clone()));
+            let successor = (*element.pointers(list_index)).next;
+            if successor.is_null() {
+                return result
+            }
+
+            // Unlink the suffix from this list.
+            (*element.pointers(list_index)).next = ptr::null_mut();
+            (*(*successor).pointers(list_index)).prev = ptr::null_mut();
+            let src_pointers = &mut (*self.pointers.get())[list_index];
+            let detached_tail = src_pointers.tail;
+            src_pointers.tail = element.holder as *mut _;
+
+            // Re-home the detached chain into the fresh multilist's final (heap) address,
+            // counting the moved objects so the per-list and total counts stay O(1)-accurate on
+            // both sides.
+            let result_ptr = &*result as *const Multilist<Value, A>;
+            let mut moved = 0;
+            let mut holder = successor;
+            while !holder.is_null() {
+                assert!((*holder).membership_count.get() == 1,
+                        "split_after: a moved element must not belong to any list other than \
+                         list_index");
+                (*holder).associated_multilist = result_ptr;
+                moved += 1;
+                holder = (*(*holder).pointers(list_index)).next;
+            }
+            src_pointers.len -= moved;
+            self.object_count.set(self.object_count.get() - moved);
+            let dst_pointers = &mut (*result.pointers.get())[list_index];
+            dst_pointers.head = successor;
+            dst_pointers.tail = detached_tail;
+            dst_pointers.len = moved;
+            result.object_count.set(moved);
+            result
+        }
+    }
+
+    /// Concatenates the list at `src_index` onto the tail of the list at `dst_index` in constant
+    /// time by joining their head/tail pointers and fixing the single boundary `next`/`prev` pair.
+    /// The source list is left empty.
+    #[inline]
+    pub fn append_list(&self, dst_index: usize, src_index: usize) {
+        unsafe {
+            let pointers = &mut *self.pointers.get();
+            let src_head = pointers[src_index].head;
+            if src_head.is_null() {
+                return
+            }
+            let src_tail = pointers[src_index].tail;
+            let dst_tail = pointers[dst_index].tail;
+            if dst_tail.is_null() {
+                pointers[dst_index].head = src_head;
+            } else {
+                (*(*dst_tail).pointers(dst_index)).next = src_head;
+                (*(*src_head).pointers(dst_index)).prev = dst_tail;
+            }
+            pointers[dst_index].tail = src_tail;
+            pointers[dst_index].len += pointers[src_index].len;
+            pointers[src_index].head = ptr::null_mut();
+            pointers[src_index].tail = ptr::null_mut();
+            pointers[src_index].len = 0;
         }
     }
 
     /// Iterates over one of the linked lists.
     #[inline]
-    pub fn iter<'a>(&'a self, list_index: usize) -> MultilistIterator<'a,Value> {
+    pub fn iter<'a>(&'a self, list_index: usize) -> MultilistIterator<'a, Value, A> {
         unsafe {
             MultilistIterator {
-                element: (*self.pointers.get())[list_index].head,
+                head: (*self.pointers.get())[list_index].head,
+                tail: (*self.pointers.get())[list_index].tail,
+                list_index: list_index,
+            }
+        }
+    }
+
+    /// Returns a read-only cursor positioned at the front of one of the lists.
+    #[inline]
+    pub fn cursor<'a>(&'a self, list_index: usize) -> MultilistCursor<'a, Value, A> {
+        unsafe {
+            MultilistCursor {
+                multilist: self,
+                current: (*self.pointers.get())[list_index].head,
+                list_index: list_index,
+            }
+        }
+    }
+
+    /// Returns an editing cursor positioned at the front of one of the lists. The cursor can splice
+    /// new elements into, and detach existing elements from, this list alone in O(1) time.
+    #[inline]
+    pub fn cursor_mut<'a>(&'a self, list_index: usize) -> MultilistCursorMut<'a, Value, A> {
+        unsafe {
+            MultilistCursorMut {
+                multilist: self,
+                current: (*self.pointers.get())[list_index].head,
                 list_index: list_index,
             }
         }
     }
 }
 
-struct MultilistElementHolder<Value> {
+struct MultilistElementHolder<Value, A: Allocator = Global> {
     value: Value,
-    associated_multilist: *const Multilist<Value>,
-    pointers: UnsafeCell<[MultilistPointers<Value>; 1]>,
+    associated_multilist: *const Multilist<Value, A>,
+    membership_count: Cell<usize>,
+    pointers: UnsafeCell<[MultilistPointers<Value, A>; 1]>,
 }
 
-impl<Value> MultilistElementHolder<Value> {
+impl<Value, A: Allocator> MultilistElementHolder<Value, A> {
     fn size(list_count: usize) -> usize {
         debug_assert!(list_count > 0);
-        mem::size_of::<MultilistElementHolder<Value>>() +
-            (mem::min_align_of::<MultilistPointers<Value>>() * (list_count - 1) as usize)
+        mem::size_of::<MultilistElementHolder<Value, A>>() +
+            (mem::min_align_of::<MultilistPointers<Value, A>>() * (list_count - 1) as usize)
 
     }
 
     #[inline]
-    fn pointers(&self, list_index: usize) -> *mut MultilistPointers<Value> {
+    fn pointers(&self, list_index: usize) -> *mut MultilistPointers<Value, A> {
         unsafe {
             debug_assert!(list_index < (*self.associated_multilist).list_count());
             (*self.pointers.get()).as_ptr().offset(list_index as isize) as
-                *mut MultilistPointers<Value>
+                *mut MultilistPointers<Value, A>
         }
     }
 }
 
 /// One element in a multilist.
-pub struct MultilistElement<'a,Value> {
-    holder: *const MultilistElementHolder<Value>,
+pub struct MultilistElement<'a, Value, A: Allocator = Global> {
+    holder: *const MultilistElementHolder<Value, A>,
 }
 
-impl<'a,Value> Copy for MultilistElement<'a,Value> {}
+impl<'a, Value, A: Allocator> Copy for MultilistElement<'a, Value, A> {}
 
-impl<'a,Value> Clone for MultilistElement<'a,Value> {
-    fn clone(&self) -> MultilistElement<'a,Value> {
+impl<'a, Value, A: Allocator> Clone for MultilistElement<'a, Value, A> {
+    fn clone(&self) -> MultilistElement<'a, Value, A> {
         *self
     }
 }
 
-impl<'a,Value> Deref for MultilistElement<'a,Value> {
+impl<'a, Value, A: Allocator> Deref for MultilistElement<'a, Value, A> {
     type Target = Value;
 
     #[inline]
@@ -202,28 +491,31 @@ impl<'a,Value> Deref for MultilistElement<'a,Value> {
     }
 }
 
-impl<'a,Value> MultilistElement<'a,Value> {
+impl<'a, Value, A: Allocator> MultilistElement<'a, Value, A> {
     #[inline]
-    fn new(value: Value, associated_multilist: &'a Multilist<Value>)
-           -> MultilistElement<'a,Value> {
+    fn new(value: Value, associated_multilist: &'a Multilist<Value, A>)
+           -> MultilistElement<'a, Value, A> {
         unsafe {
             let byte_size =
-                MultilistElementHolder::<Value>::size((*associated_multilist.pointers
-                                                                            .get()).len());
-            let holder = heap::allocate(byte_size, byte_size) as
-                *mut MultilistElementHolder<Value>;
+                MultilistElementHolder::<Value, A>::size((*associated_multilist.pointers
+                                                                               .get()).len());
+            let holder = associated_multilist.allocator.allocate(byte_size, byte_size) as
+                *mut MultilistElementHolder<Value, A>;
             if holder.is_null() {
                 alloc::oom()
             }
             ptr::write(holder, MultilistElementHolder {
                 value: value,
                 associated_multilist: associated_multilist,
+                membership_count: Cell::new(0),
                 pointers: UnsafeCell::new([MultilistPointers::new()]),
             });
-            for i in range(mem::size_of::<MultilistElement<Value>>(), byte_size) {
+            for i in range(mem::size_of::<MultilistElement<Value, A>>(), byte_size) {
                 ptr::write((*(*holder).pointers.get()).as_mut_ptr().offset(i as isize),
                            MultilistPointers::new())
             }
+            let object_count = &associated_multilist.object_count;
+            object_count.set(object_count.get() + 1);
             MultilistElement {
                 holder: holder,
             }
@@ -231,12 +523,12 @@ impl<'a,Value> MultilistElement<'a,Value> {
     }
 
     #[inline]
-    unsafe fn pointers(&self, list_index: usize) -> *mut MultilistPointers<Value> {
+    unsafe fn pointers(&self, list_index: usize) -> *mut MultilistPointers<Value, A> {
         (*self.holder).pointers(list_index)
     }
 
     #[inline]
-    fn associated_multilist(&self) -> *const Multilist<Value> {
+    fn associated_multilist(&self) -> *const Multilist<Value, A> {
         unsafe {
             (*self.holder).associated_multilist
         }
@@ -244,10 +536,12 @@ impl<'a,Value> MultilistElement<'a,Value> {
 
     #[inline]
     unsafe fn destroy(&mut self) {
+        let multilist = self.associated_multilist();
+        let object_count = &(*multilist).object_count;
+        object_count.set(object_count.get() - 1);
         let byte_size =
-            MultilistElementHolder::<Value>::size((*(*self.associated_multilist()).pointers
-                                                                                  .get()).len());
-        drop(heap::deallocate(self.holder as *mut u8, byte_size, byte_size))
+            MultilistElementHolder::<Value, A>::size((*(*multilist).pointers.get()).len());
+        (*multilist).allocator.deallocate(self.holder as *mut u8, byte_size, byte_size)
     }
 
     /// Returns true if this element is a member of the given list.
@@ -260,21 +554,21 @@ impl<'a,Value> MultilistElement<'a,Value> {
     }
 }
 
-pub struct MultilistPointers<Value> {
-    next: *mut MultilistElementHolder<Value>,
-    prev: *mut MultilistElementHolder<Value>,
+pub struct MultilistPointers<Value, A: Allocator = Global> {
+    next: *mut MultilistElementHolder<Value, A>,
+    prev: *mut MultilistElementHolder<Value, A>,
 }
 
-impl<Value> Copy for MultilistPointers<Value> {}
+impl<Value, A: Allocator> Copy for MultilistPointers<Value, A> {}
 
-impl<Value> Clone for MultilistPointers<Value> {
-    fn clone(&self) -> MultilistPointers<Value> {
+impl<Value, A: Allocator> Clone for MultilistPointers<Value, A> {
+    fn clone(&self) -> MultilistPointers<Value, A> {
         *self
     }
 }
 
-impl<Value> MultilistPointers<Value> {
-    pub fn new() -> MultilistPointers<Value> {
+impl<Value, A: Allocator> MultilistPointers<Value, A> {
+    pub fn new() -> MultilistPointers<Value, A> {
         MultilistPointers {
             next: ptr::null_mut(),
             prev: ptr::null_mut(),
@@ -282,45 +576,73 @@ impl<Value> MultilistPointers<Value> {
     }
 }
 
-pub struct MultilistListPointers<Value> {
-    head: *mut MultilistElementHolder<Value>,
-    tail: *mut MultilistElementHolder<Value>,
+pub struct MultilistListPointers<Value, A: Allocator = Global> {
+    head: *mut MultilistElementHolder<Value, A>,
+    tail: *mut MultilistElementHolder<Value, A>,
+    len: usize,
 }
 
-impl<Value> Copy for MultilistListPointers<Value> {}
+impl<Value, A: Allocator> Copy for MultilistListPointers<Value, A> {}
 
-impl<Value> Clone for MultilistListPointers<Value> {
-    fn clone(&self) -> MultilistListPointers<Value> {
+impl<Value, A: Allocator> Clone for MultilistListPointers<Value, A> {
+    fn clone(&self) -> MultilistListPointers<Value, A> {
         *self
     }
 }
 
-impl<Value> MultilistListPointers<Value> {
-    pub fn new() -> MultilistListPointers<Value> {
+impl<Value, A: Allocator> MultilistListPointers<Value, A> {
+    pub fn new() -> MultilistListPointers<Value, A> {
         MultilistListPointers {
             head: ptr::null_mut(),
             tail: ptr::null_mut(),
+            len: 0,
         }
     }
 }
 
-pub struct MultilistIterator<'a,Value> {
-    element: *mut MultilistElementHolder<Value>,
+pub struct MultilistIterator<'a, Value, A: Allocator = Global> {
+    head: *mut MultilistElementHolder<Value, A>,
+    tail: *mut MultilistElementHolder<Value, A>,
     list_index: usize,
 }
 
-impl<'a,Value> Iterator for MultilistIterator<'a,Value> {
-    type Item = MultilistElement<'a,Value>;
+impl<'a, Value, A: Allocator> Iterator for MultilistIterator<'a, Value, A> {
+    type Item = MultilistElement<'a, Value, A>;
+
+    fn next(&mut self) -> Option<MultilistElement<'a, Value, A>> {
+        let element = self.head;
+        if element.is_null() {
+            return None
+        }
+
+        unsafe {
+            if element == self.tail {
+                self.head = ptr::null_mut();
+                self.tail = ptr::null_mut();
+            } else {
+                self.head = (*(*element).pointers(self.list_index)).next;
+            }
+            Some(MultilistElement {
+                holder: element,
+            })
+        }
+    }
+}
 
-    fn next(&mut self) -> Option<MultilistElement<'a,Value>> {
-        let element = self.element;
+impl<'a, Value, A: Allocator> DoubleEndedIterator for MultilistIterator<'a, Value, A> {
+    fn next_back(&mut self) -> Option<MultilistElement<'a, Value, A>> {
+        let element = self.tail;
         if element.is_null() {
             return None
         }
 
         unsafe {
-            let next = (*(*element).pointers(self.list_index)).next;
-            self.element = next;
+            if element == self.head {
+                self.head = ptr::null_mut();
+                self.tail = ptr::null_mut();
+            } else {
+                self.tail = (*(*element).pointers(self.list_index)).prev;
+            }
             Some(MultilistElement {
                 holder: element,
             })
@@ -328,67 +650,331 @@ impl<'a,Value> Iterator for MultilistIterator<'a,Value> {
     }
 }
 
-/// Example code. This is skeleton code that shows how this might be used in an operating system
-/// kernel to manage tasks.
-#[allow(dead_code)]
-fn main() {
-    #[derive(Debug)]
-    struct TaskStruct {
-        pid: i32,
-        gid: i32,
-    }
-
-    const TASK_LIST: usize = 0;
-    const RUN_LIST: usize = 1;
-
-    let mut multilist = Multilist::new(2);
-    multilist.push_back(TASK_LIST, TaskStruct {
-        pid: 1,
-        gid: 2,
-    });
-    multilist.push_back(TASK_LIST, TaskStruct {
-        pid: 3,
-        gid: 4,
-    });
-    multilist.push_back(TASK_LIST, TaskStruct {
-        pid: 5,
-        gid: 6,
-    });
-    println!("After adding 3 tasks to task list:");
-    dump_list(&multilist);
-
-    multilist.push_back_existing(RUN_LIST, multilist.iter(TASK_LIST).skip(2).next().unwrap());
-    multilist.push_back_existing(RUN_LIST, multilist.iter(TASK_LIST).skip(0).next().unwrap());
-    multilist.push_back_existing(RUN_LIST, multilist.iter(TASK_LIST).skip(1).next().unwrap());
-    println!("\nAfter adding 3 tasks to run list in order 2, 0, 1:");
-    dump_list(&multilist);
-
-    multilist.remove_existing(TASK_LIST, multilist.iter(TASK_LIST).skip(1).next().unwrap());
-    println!("\nAfter removing the second task from the task list:");
-    dump_list(&multilist);
-
-    multilist.push_back(TASK_LIST, TaskStruct {
-        pid: 7,
-        gid: 8,
-    });
-    println!("\nAfter adding a new task to the task list:");
-    dump_list(&multilist);
-
-    multilist.pop_back(RUN_LIST);
-    println!("\nAfter removing the last task on the run list entirely:");
-    dump_list(&multilist);
-
-    return;
-
-    fn dump_list(multilist: &Multilist<TaskStruct>) {
-        println!("Tasks in task order:");
-        for task in multilist.iter(TASK_LIST) {
-            println!("{:?}", &*task);
-        }
-        println!("Tasks in run order:");
-        for task in multilist.iter(RUN_LIST) {
-            println!("{:?}", &*task);
+/// A read-only cursor into one of the linked lists. Unlike an iterator, a cursor can walk in both
+/// directions over the same list without being consumed.
+pub struct MultilistCursor<'a, Value, A: Allocator = Global> {
+    multilist: &'a Multilist<Value, A>,
+    current: *mut MultilistElementHolder<Value, A>,
+    list_index: usize,
+}
+
+impl<'a, Value, A: Allocator> MultilistCursor<'a, Value, A> {
+    /// Moves the cursor to the following element of the list, if any.
+    #[inline]
+    pub fn move_next(&mut self) {
+        if !self.current.is_null() {
+            unsafe {
+                self.current = (*(*self.current).pointers(self.list_index)).next;
+            }
+        }
+    }
+
+    /// Moves the cursor to the preceding element of the list, if any.
+    #[inline]
+    pub fn move_prev(&mut self) {
+        if !self.current.is_null() {
+            unsafe {
+                self.current = (*(*self.current).pointers(self.list_index)).prev;
+            }
+        }
+    }
+
+    /// Returns the element the cursor currently points at, or `None` if it has walked off the end
+    /// of the list.
+    #[inline]
+    pub fn current(&self) -> Option<MultilistElement<'a, Value, A>> {
+        if self.current.is_null() {
+            None
+        } else {
+            Some(MultilistElement {
+                holder: self.current,
+            })
+        }
+    }
+}
+
+/// An editing cursor into one of the linked lists. In addition to the navigation offered by
+/// `MultilistCursor`, it can splice elements into and out of the cursor's list in O(1) time without
+/// re-iterating from the head.
+pub struct MultilistCursorMut<'a, Value, A: Allocator = Global> {
+    multilist: &'a Multilist<Value, A>,
+    current: *mut MultilistElementHolder<Value, A>,
+    list_index: usize,
+}
+
+impl<'a, Value, A: Allocator> MultilistCursorMut<'a, Value, A> {
+    /// Moves the cursor to the following element of the list, if any.
+    #[inline]
+    pub fn move_next(&mut self) {
+        if !self.current.is_null() {
+            unsafe {
+                self.current = (*(*self.current).pointers(self.list_index)).next;
+            }
+        }
+    }
+
+    /// Moves the cursor to the preceding element of the list, if any.
+    #[inline]
+    pub fn move_prev(&mut self) {
+        if !self.current.is_null() {
+            unsafe {
+                self.current = (*(*self.current).pointers(self.list_index)).prev;
+            }
+        }
+    }
+
+    /// Returns the element the cursor currently points at, or `None` if it has walked off the end
+    /// of the list.
+    #[inline]
+    pub fn current(&self) -> Option<MultilistElement<'a, Value, A>> {
+        if self.current.is_null() {
+            None
+        } else {
+            Some(MultilistElement {
+                holder: self.current,
+            })
+        }
+    }
+
+    /// Inserts a brand-new element into the cursor's list immediately after the current element and
+    /// returns it. If the cursor has walked off the end of the list, the element is inserted at the
+    /// front.
+    #[inline]
+    pub fn insert_after(&mut self, value: Value) -> MultilistElement<'a, Value, A> {
+        let element = MultilistElement::new(value, self.multilist);
+        unsafe {
+            let (prev, next) = if self.current.is_null() {
+                (ptr::null_mut(), (*self.multilist.pointers.get())[self.list_index].head)
+            } else {
+                (self.current, (*(*self.current).pointers(self.list_index)).next)
+            };
+            self.multilist.splice_in(self.list_index, element, prev, next);
+        }
+        element
+    }
+
+    /// Inserts a brand-new element into the cursor's list immediately before the current element
+    /// and returns it. If the cursor has walked off the end of the list, the element is inserted at
+    /// the back.
+    #[inline]
+    pub fn insert_before(&mut self, value: Value) -> MultilistElement<'a, Value, A> {
+        let element = MultilistElement::new(value, self.multilist);
+        unsafe {
+            let (prev, next) = if self.current.is_null() {
+                ((*self.multilist.pointers.get())[self.list_index].tail, ptr::null_mut())
+            } else {
+                ((*(*self.current).pointers(self.list_index)).prev, self.current)
+            };
+            self.multilist.splice_in(self.list_index, element, prev, next);
+        }
+        element
+    }
+
+    /// Detaches the current element from the cursor's list, advances the cursor to what was the
+    /// following element, and returns that element (if any).
+    ///
+    /// As with `remove_existing()`, if the cursor's list was the element's last list the element is
+    /// torn down and its value dropped; no leak occurs.
+    #[inline]
+    pub fn remove_current(&mut self) -> Option<MultilistElement<'a, Value, A>> {
+        if self.current.is_null() {
+            return None
+        }
+        unsafe {
+            let element = MultilistElement {
+                holder: self.current,
+            };
+            let next = (*(*self.current).pointers(self.list_index)).next;
+            self.multilist.remove_existing(self.list_index, element);
+            self.current = next;
+            self.current()
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::Multilist;
+
+    const ONLY_LIST: usize = 0;
+
+    #[test]
+    fn push_front_pop_front_and_double_ended_iteration() {
+        let mut multilist = Multilist::new(1);
+        multilist.push_back(ONLY_LIST, 2);
+        multilist.push_front(ONLY_LIST, 1);
+        multilist.push_back(ONLY_LIST, 3);
+
+        let mut iter = multilist.iter(ONLY_LIST);
+        assert_eq!(*iter.next().unwrap(), 1);
+        assert_eq!(*iter.next_back().unwrap(), 3);
+        assert_eq!(*iter.next().unwrap(), 2);
+        assert!(iter.next().is_none());
+
+        assert_eq!(multilist.pop_front(ONLY_LIST), Some(1));
+        assert_eq!(multilist.pop_front(ONLY_LIST), Some(2));
+        assert_eq!(multilist.pop_front(ONLY_LIST), Some(3));
+        assert_eq!(multilist.pop_front(ONLY_LIST), None);
+    }
+
+    #[test]
+    fn split_after_re_homes_elements_to_a_stable_address() {
+        let multilist = Multilist::new(1);
+        multilist.push_back(ONLY_LIST, 1);
+        multilist.push_back(ONLY_LIST, 2);
+        multilist.push_back(ONLY_LIST, 3);
+
+        let first = multilist.iter(ONLY_LIST).next().unwrap();
+        let mut suffix = multilist.split_after(ONLY_LIST, first);
+
+        // Exercising ops on the result proves its elements' `associated_multilist` pointers
+        // survived the move out of `split_after` (they would otherwise dangle into its stack
+        // frame and trip the `assert!` in `remove_existing`, which `pop_back` also goes through).
+        let moved = suffix.iter(ONLY_LIST).next().unwrap();
+        suffix.remove_existing(ONLY_LIST, moved);
+        assert_eq!(suffix.pop_back(ONLY_LIST), Some(3));
+        assert_eq!(suffix.pop_back(ONLY_LIST), None);
+        assert_eq!(multilist.len(ONLY_LIST), 1);
+    }
+
+    #[test]
+    fn cursor_navigation_and_in_place_editing() {
+        let multilist = Multilist::new(1);
+        multilist.push_back(ONLY_LIST, 1);
+        multilist.push_back(ONLY_LIST, 3);
+
+        let mut cursor = multilist.cursor_mut(ONLY_LIST);
+        assert_eq!(*cursor.current().unwrap(), 1);
+        cursor.insert_after(2);
+        cursor.move_next();
+        assert_eq!(*cursor.current().unwrap(), 2);
+        cursor.move_next();
+        assert_eq!(*cursor.current().unwrap(), 3);
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(*cursor.current().unwrap(), 1);
+
+        // Detaching the current element in O(1) time advances the cursor to what follows it.
+        assert_eq!(*cursor.remove_current().unwrap(), 2);
+
+        let mut read_only = multilist.cursor(ONLY_LIST);
+        assert_eq!(*read_only.current().unwrap(), 2);
+        read_only.move_next();
+        assert_eq!(*read_only.current().unwrap(), 3);
+        read_only.move_next();
+        assert!(read_only.current().is_none());
+    }
+
+    #[test]
+    fn cursor_inserted_elements_do_not_leak() {
+        let mut multilist = Multilist::new(1);
+        let mut cursor = multilist.cursor_mut(ONLY_LIST);
+        cursor.insert_after(1);
+        cursor.insert_before(2);
+
+        // If `membership_count` were never incremented for cursor insertions, this `pop_back`
+        // would underflow the count instead of tearing the element down and returning its value.
+        assert_eq!(multilist.pop_back(ONLY_LIST), Some(2));
+        assert_eq!(multilist.pop_back(ONLY_LIST), Some(1));
+        assert_eq!(multilist.pop_back(ONLY_LIST), None);
+    }
+
+    #[test]
+    fn custom_allocator_backs_every_holder_allocation() {
+        use super::{Allocator, Cell, Global};
+
+        struct CountingAllocator {
+            allocations: Cell<usize>,
+            deallocations: Cell<usize>,
+        }
+
+        impl<'a> Allocator for &'a CountingAllocator {
+            unsafe fn allocate(&self, size: usize, align: usize) -> *mut u8 {
+                self.allocations.set(self.allocations.get() + 1);
+                Global.allocate(size, align)
+            }
+
+            unsafe fn deallocate(&self, ptr: *mut u8, size: usize, align: usize) {
+                self.deallocations.set(self.deallocations.get() + 1);
+                Global.deallocate(ptr, size, align)
+            }
+        }
+
+        let allocator = CountingAllocator {
+            allocations: Cell::new(0),
+            deallocations: Cell::new(0),
+        };
+        let mut multilist = Multilist::new_in(1, &allocator);
+        multilist.push_back(ONLY_LIST, 1);
+        multilist.push_back(ONLY_LIST, 2);
+        assert_eq!(allocator.allocations.get(), 2);
+        assert_eq!(allocator.deallocations.get(), 0);
+
+        assert_eq!(multilist.pop_back(ONLY_LIST), Some(2));
+        assert_eq!(allocator.deallocations.get(), 1);
+        assert_eq!(multilist.pop_back(ONLY_LIST), Some(1));
+        assert_eq!(allocator.deallocations.get(), 2);
+    }
+
+    #[test]
+    fn len_and_total_objects_stay_accurate_across_list_ops() {
+        const LIST_A: usize = 0;
+        const LIST_B: usize = 1;
+        let mut multilist = Multilist::new(2);
+
+        multilist.push_back(LIST_A, 1);
+        multilist.push_front(LIST_A, 0);
+        multilist.push_back(LIST_A, 2);
+        assert_eq!(multilist.len(LIST_A), 3);
+        assert_eq!(multilist.total_objects(), 3);
+
+        // Adding an element already in LIST_A to LIST_B grows LIST_B's length, but not the total
+        // object count: it's still the same object, just newly a member of a second list.
+        let middle = {
+            let mut cursor = multilist.cursor(LIST_A);
+            cursor.move_next();
+            cursor.current().unwrap()
+        };
+        multilist.push_back_existing(LIST_B, middle);
+        assert_eq!(multilist.len(LIST_B), 1);
+        assert_eq!(multilist.total_objects(), 3);
+
+        // Removing it from just one of its two lists leaves the object, and the total count,
+        // alone.
+        multilist.remove_existing(LIST_A, middle);
+        assert_eq!(multilist.len(LIST_A), 2);
+        assert_eq!(multilist.total_objects(), 3);
+
+        // Removing it from its last remaining list tears it down and drops the total.
+        multilist.remove_existing(LIST_B, middle);
+        assert_eq!(multilist.len(LIST_B), 0);
+        assert_eq!(multilist.total_objects(), 2);
+
+        assert_eq!(multilist.pop_front(LIST_A), Some(0));
+        assert_eq!(multilist.len(LIST_A), 1);
+        assert_eq!(multilist.total_objects(), 1);
+
+        multilist.push_back(LIST_A, 4);
+        multilist.push_back(LIST_A, 5);
+        assert_eq!(multilist.len(LIST_A), 3);
+        assert_eq!(multilist.total_objects(), 3);
+
+        // split_after moves its suffix's length and object count onto the returned multilist and
+        // subtracts them from this one — this is exactly the bookkeeping a dangling
+        // associated_multilist pointer (or a skipped cross-list-membership check) would corrupt.
+        let first = multilist.iter(LIST_A).next().unwrap();
+        let suffix = multilist.split_after(LIST_A, first);
+        assert_eq!(multilist.len(LIST_A), 1);
+        assert_eq!(multilist.total_objects(), 1);
+        assert_eq!(suffix.len(LIST_A), 2);
+        assert_eq!(suffix.total_objects(), 2);
+
+        // append_list only re-links two of this multilist's own lists, so it moves length between
+        // them without touching the total object count at all.
+        multilist.push_back(LIST_B, 6);
+        multilist.append_list(LIST_A, LIST_B);
+        assert_eq!(multilist.len(LIST_A), 2);
+        assert_eq!(multilist.len(LIST_B), 0);
+        assert_eq!(multilist.total_objects(), 2);
+    }
+}